@@ -0,0 +1,81 @@
+use std::collections::BTreeSet;
+
+// Tracks the farthest point reached during a parse and what was expected there,
+// so a failure deep in a long `Choice` doesn't get reported at whichever
+// alternative happened to run last. Every leaf matcher (`Terminal`, `CharClass`,
+// `Call`) records what it expected when it fails; `Choice` merges the
+// expected-sets of its alternatives at the same offset.
+#[derive(Debug, Default)]
+pub struct ParseErrorState {
+    farthest: usize,
+    expected: BTreeSet<String>,
+}
+
+impl ParseErrorState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Record that `what` was expected at `offset`. An offset past the current
+    // farthest point resets the expected-set; an offset behind it is ignored,
+    // since it can't be the real cause of failure.
+    pub fn record(&mut self, offset: usize, what: impl Into<String>) {
+        if offset > self.farthest {
+            self.farthest = offset;
+            self.expected.clear();
+        }
+        if offset == self.farthest {
+            self.expected.insert(what.into());
+        }
+    }
+
+    pub fn farthest_offset(&self) -> usize {
+        self.farthest
+    }
+
+    pub fn expected(&self) -> impl Iterator<Item = &str> {
+        self.expected.iter().map(String::as_str)
+    }
+
+    // Scans `input` for newlines up to the farthest offset to turn that byte
+    // offset into a 1-based (line, column) pair.
+    pub fn line_column(&self, input: &str) -> (usize, usize) {
+        let mut line = 1;
+        let mut col = 1;
+        for ch in input[..self.farthest.min(input.len())].chars() {
+            if ch == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        (line, col)
+    }
+
+    // Renders `expected "+" or digit at line 3, column 12, found '}'`.
+    pub fn render(&self, input: &str) -> String {
+        let (line, col) = self.line_column(input);
+        let found = input[self.farthest.min(input.len())..]
+            .chars()
+            .next()
+            .map(|c| format!("'{}'", c))
+            .unwrap_or_else(|| "end of input".to_string());
+
+        let expected: Vec<&str> = self.expected().collect();
+        let expected = match expected.len() {
+            0 => "more input".to_string(),
+            1 => expected[0].to_string(),
+            2 => format!("{} or {}", expected[0], expected[1]),
+            _ => {
+                let (last, rest) = expected.split_last().unwrap();
+                format!("{}, or {}", rest.join(", "), last)
+            }
+        };
+
+        format!(
+            "expected {} at line {}, column {}, found {}",
+            expected, line, col, found
+        )
+    }
+}
@@ -0,0 +1,594 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use crate::error::ParseErrorState;
+use crate::parser::{ParseTree, RuleOptions};
+
+// One successful match: how many bytes of input it consumed from the offset
+// it started at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matched {
+    pub consumed: usize,
+}
+
+// An ordinary PEG failure lets the enclosing `Choice` try its next alternative.
+// A failure past a `Cut` is `Hard`: it propagates through enclosing `Choice`
+// nodes (and aborts an enclosing repetition) instead of being backtracked out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Soft,
+    Hard,
+}
+
+pub type EvalResult = Result<Matched, Failure>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum MemoEntry {
+    Failed,
+    Matched(Matched),
+}
+
+// A grammar ready to be interpreted: every `ParserDefinition` from a parsed
+// `ParseTree::DefinitionList`, indexed by rule name.
+pub struct Grammar {
+    rules: HashMap<String, (RuleOptions, ParseTree)>,
+}
+
+impl Grammar {
+    pub fn from_definitions(tree: ParseTree) -> Self {
+        let mut rules = HashMap::new();
+
+        if let ParseTree::DefinitionList(defs) = tree {
+            for def in defs {
+                if let ParseTree::ParserDefinition(name, _return_type, body, options) = def {
+                    rules.insert(name.to_string(), (options, *body));
+                }
+            }
+        }
+
+        Grammar { rules }
+    }
+}
+
+// Frame pushed while seed-growing a `left_recursive` rule, used to tell rules
+// that are merely *marked* left-recursive apart from ones that are actually
+// reached recursively at a given offset.
+struct LrFrame {
+    key: (String, usize),
+    involved: bool,
+    // every other memoized rule's (name, offset) key that got computed and
+    // cached while this frame's body was running; since those results may
+    // depend on the seed being grown, they have to be evicted from the memo
+    // table and recomputed on every grow iteration, not just this frame's own
+    // key, or a stale `Failed` cached before the seed first succeeded will
+    // wrongly stick around forever
+    touched: HashSet<(String, usize)>,
+}
+
+// Tree-walking evaluator for a `Grammar`. A memo table, keyed by (rule name,
+// byte offset), backs both the packrat `#[memoize]` fast path and the
+// `#[left_recursive]` seed-growing loop.
+pub struct Interp<'g> {
+    grammar: &'g Grammar,
+    memo: RefCell<HashMap<(String, usize), MemoEntry>>,
+    lr_stack: RefCell<Vec<LrFrame>>,
+    errors: RefCell<ParseErrorState>,
+}
+
+impl<'g> Interp<'g> {
+    pub fn new(grammar: &'g Grammar) -> Self {
+        Interp {
+            grammar,
+            memo: RefCell::new(HashMap::new()),
+            lr_stack: RefCell::new(Vec::new()),
+            errors: RefCell::new(ParseErrorState::new()),
+        }
+    }
+
+    pub fn errors(&self) -> std::cell::Ref<'_, ParseErrorState> {
+        self.errors.borrow()
+    }
+
+    fn record_failure(&self, tree: &ParseTree, offset: usize) {
+        let label = tree.expected_label().unwrap_or_else(|| "more input".to_string());
+        self.errors.borrow_mut().record(offset, label);
+    }
+
+    pub fn eval_rule(&self, name: &str, input: &str, offset: usize) -> EvalResult {
+        let (options, body) = match self.grammar.rules.get(name) {
+            Some(rule) => rule,
+            None => {
+                self.errors.borrow_mut().record(offset, format!("rule `{}`", name));
+                return Err(Failure::Soft);
+            }
+        };
+
+        if options.left_recursive {
+            return self.eval_left_recursive(name, body, input, offset);
+        }
+
+        if !options.memoize {
+            return self.eval_tree(body, input, offset);
+        }
+
+        let key = (name.to_string(), offset);
+        if let Some(entry) = self.memo.borrow().get(&key) {
+            return match entry {
+                MemoEntry::Failed => Err(Failure::Soft),
+                MemoEntry::Matched(m) => Ok(m.clone()),
+            };
+        }
+
+        let result = self.eval_tree(body, input, offset);
+        self.memo.borrow_mut().insert(
+            key.clone(),
+            match &result {
+                Ok(m) => MemoEntry::Matched(m.clone()),
+                Err(_) => MemoEntry::Failed,
+            },
+        );
+        // record this rule's key against every left-recursive rule currently
+        // growing a seed, so it gets re-evaluated (instead of reusing this
+        // now-possibly-stale cache entry) on that rule's next grow iteration
+        for frame in self.lr_stack.borrow_mut().iter_mut() {
+            frame.touched.insert(key.clone());
+        }
+        result
+    }
+
+    // Warth's seed-growing algorithm: seed the memo with failure, run the body
+    // once, then keep re-running it from the same start and growing the memo
+    // entry as long as each pass consumes strictly more input than the last.
+    // The left-recursive call inside `body` reads the current seed back out of
+    // the memo table via the memo lookup at the top of this function.
+    fn eval_left_recursive(
+        &self,
+        name: &str,
+        body: &ParseTree,
+        input: &str,
+        offset: usize,
+    ) -> EvalResult {
+        let key = (name.to_string(), offset);
+
+        if let Some(entry) = self.memo.borrow().get(&key) {
+            // Already seeding/growing this exact (rule, offset) further up the
+            // call stack: this call is the recursive one, so mark the frame
+            // that's doing the growing as genuinely left-recursive.
+            if let Some(frame) = self
+                .lr_stack
+                .borrow_mut()
+                .iter_mut()
+                .rev()
+                .find(|f| f.key == key)
+            {
+                frame.involved = true;
+            }
+            return match entry {
+                MemoEntry::Failed => Err(Failure::Soft),
+                MemoEntry::Matched(m) => Ok(m.clone()),
+            };
+        }
+
+        self.memo.borrow_mut().insert(key.clone(), MemoEntry::Failed);
+        // kept on the stack for the rest of this function, including every
+        // grow iteration below, so nested memoized rules keep recording
+        // themselves into `touched` for as long as this seed keeps growing
+        self.lr_stack.borrow_mut().push(LrFrame {
+            key: key.clone(),
+            involved: false,
+            touched: HashSet::new(),
+        });
+
+        let first = self.eval_tree(body, input, offset);
+        let involved = self.lr_stack.borrow().last().map(|f| f.involved).unwrap_or(false);
+
+        let mut seed = match first {
+            Ok(m) => m,
+            Err(f) => {
+                self.lr_stack.borrow_mut().pop();
+                self.memo.borrow_mut().remove(&key);
+                return Err(f);
+            }
+        };
+
+        if !involved {
+            // Marked `#[left_recursive]` but never actually reached itself at
+            // this offset: an ordinary memoized rule, no growing needed.
+            self.lr_stack.borrow_mut().pop();
+            self.memo.borrow_mut().insert(key, MemoEntry::Matched(seed.clone()));
+            return Ok(seed);
+        }
+
+        loop {
+            let touched = self
+                .lr_stack
+                .borrow()
+                .last()
+                .map(|f| f.touched.clone())
+                .unwrap_or_default();
+            let mut memo = self.memo.borrow_mut();
+            for touched_key in &touched {
+                // evict everything this rule's body reached last time, since
+                // any of it may have read the seed we're about to grow
+                if touched_key != &key {
+                    memo.remove(touched_key);
+                }
+            }
+            memo.insert(key.clone(), MemoEntry::Matched(seed.clone()));
+            drop(memo);
+
+            match self.eval_tree(body, input, offset) {
+                Ok(next) if next.consumed > seed.consumed => seed = next,
+                _ => break,
+            }
+        }
+
+        self.lr_stack.borrow_mut().pop();
+        self.memo.borrow_mut().insert(key, MemoEntry::Matched(seed.clone()));
+        Ok(seed)
+    }
+
+    fn eval_tree(&self, tree: &ParseTree, input: &str, offset: usize) -> EvalResult {
+        match tree {
+            ParseTree::DefinitionList(_) | ParseTree::ParserDefinition(..) => {
+                unreachable!("grammar-level nodes are never evaluated directly")
+            }
+
+            ParseTree::Capture(inner, _) => self.eval_tree(inner, input, offset),
+
+            ParseTree::NonTerminal(ident) => self.eval_rule(&ident.to_string(), input, offset),
+
+            // no host-language function table is wired up in this interpreter
+            ParseTree::Call(_ident) => {
+                self.record_failure(tree, offset);
+                Err(Failure::Soft)
+            }
+
+            ParseTree::Sequence(items, _block) => self.eval_sequence(items, input, offset),
+
+            ParseTree::Empty => Ok(Matched { consumed: 0 }),
+
+            ParseTree::Terminal(s) => {
+                if input[offset..].starts_with(s.as_str()) {
+                    Ok(Matched { consumed: s.len() })
+                } else {
+                    self.record_failure(tree, offset);
+                    Err(Failure::Soft)
+                }
+            }
+
+            ParseTree::CharClass { ranges, negated } => match input[offset..].chars().next() {
+                Some(c) => {
+                    let in_ranges = ranges.iter().any(|&(lo, hi)| c >= lo && c <= hi);
+                    if in_ranges != *negated {
+                        Ok(Matched { consumed: c.len_utf8() })
+                    } else {
+                        self.record_failure(tree, offset);
+                        Err(Failure::Soft)
+                    }
+                }
+                None => {
+                    self.record_failure(tree, offset);
+                    Err(Failure::Soft)
+                }
+            },
+
+            ParseTree::Choice(alts) => {
+                for alt in alts {
+                    match self.eval_tree(alt, input, offset) {
+                        Ok(m) => return Ok(m),
+                        Err(Failure::Hard) => return Err(Failure::Hard),
+                        Err(Failure::Soft) => continue,
+                    }
+                }
+                Err(Failure::Soft)
+            }
+
+            ParseTree::Many0(inner) => self.eval_repeat(inner, input, offset, 0, None),
+            ParseTree::Many1(inner) => self.eval_repeat(inner, input, offset, 1, None),
+            ParseTree::Optional(inner) => self.eval_repeat(inner, input, offset, 0, Some(1)),
+
+            ParseTree::Peek(inner) => match self.eval_tree(inner, input, offset) {
+                Ok(_) => Ok(Matched { consumed: 0 }),
+                Err(f) => Err(f),
+            },
+
+            ParseTree::Not(inner) => match self.eval_tree(inner, input, offset) {
+                Ok(_) => Err(Failure::Soft),
+                Err(_) => Ok(Matched { consumed: 0 }),
+            },
+
+            // reached only if a `Cut` ever appears outside a `Sequence`'s
+            // element list; `eval_sequence` handles the commit behavior itself
+            ParseTree::Cut(inner) => self.eval_tree(inner, input, offset),
+
+            ParseTree::Repeat { item, min, max } => {
+                self.eval_repeat(item, input, offset, *min, *max)
+            }
+
+            ParseTree::SepBy { item, sep, min, allow_trailing } => {
+                self.eval_sepby(item, sep, input, offset, *min, *allow_trailing)
+            }
+        }
+    }
+
+    // A cut commits its *enclosing sequence*: once a `Cut`-wrapped element
+    // matches, every later failure in this same sequence becomes `Hard` and
+    // propagates past enclosing `Choice`/repetition nodes instead of letting
+    // them backtrack into a different alternative or just stop early.
+    fn eval_sequence(&self, items: &[ParseTree], input: &str, offset: usize) -> EvalResult {
+        let mut pos = offset;
+        let mut committed = false;
+
+        for item in items {
+            let (target, is_cut) = match item {
+                ParseTree::Cut(inner) => (inner.as_ref(), true),
+                other => (other, false),
+            };
+
+            match self.eval_tree(target, input, pos) {
+                Ok(m) => {
+                    pos += m.consumed;
+                    if is_cut {
+                        committed = true;
+                    }
+                }
+                Err(f) => return Err(if committed { Failure::Hard } else { f }),
+            }
+        }
+
+        Ok(Matched { consumed: pos - offset })
+    }
+
+    fn eval_repeat(
+        &self,
+        inner: &ParseTree,
+        input: &str,
+        offset: usize,
+        min: usize,
+        max: Option<usize>,
+    ) -> EvalResult {
+        let mut pos = offset;
+        let mut count = 0usize;
+
+        loop {
+            if let Some(max) = max {
+                if count >= max {
+                    break;
+                }
+            }
+
+            match self.eval_tree(inner, input, pos) {
+                Ok(m) => {
+                    let zero_width = m.consumed == 0;
+                    pos += m.consumed;
+                    count += 1;
+                    if zero_width && max.is_none() {
+                        // an unbounded repetition matching nothing would loop forever
+                        break;
+                    }
+                }
+                // a cut past the point of no return aborts the whole repetition
+                // rather than just ending it with whatever was matched so far
+                Err(Failure::Hard) => return Err(Failure::Hard),
+                Err(Failure::Soft) => break,
+            }
+        }
+
+        if count < min {
+            Err(Failure::Soft)
+        } else {
+            Ok(Matched { consumed: pos - offset })
+        }
+    }
+
+    fn eval_sepby(
+        &self,
+        item: &ParseTree,
+        sep: &ParseTree,
+        input: &str,
+        offset: usize,
+        min: usize,
+        allow_trailing: bool,
+    ) -> EvalResult {
+        let mut pos = offset;
+        let mut count = 0usize;
+
+        loop {
+            let before_sep = pos;
+
+            if count > 0 {
+                match self.eval_tree(sep, input, pos) {
+                    Ok(m) => pos += m.consumed,
+                    // a cut past the point of no return aborts the whole list
+                    // rather than just ending it with whatever matched so far
+                    Err(Failure::Hard) => return Err(Failure::Hard),
+                    Err(Failure::Soft) => break,
+                }
+            }
+
+            match self.eval_tree(item, input, pos) {
+                Ok(m) => {
+                    pos += m.consumed;
+                    count += 1;
+                }
+                Err(Failure::Hard) => return Err(Failure::Hard),
+                Err(Failure::Soft) => {
+                    pos = before_sep;
+                    break;
+                }
+            }
+        }
+
+        if allow_trailing {
+            if let Ok(m) = self.eval_tree(sep, input, pos) {
+                pos += m.consumed;
+            }
+        }
+
+        if count < min {
+            Err(Failure::Soft)
+        } else {
+            Ok(Matched { consumed: pos - offset })
+        }
+    }
+}
+
+// Matches `rule` against the start of `input`, returning the number of bytes
+// consumed.
+pub fn parse(grammar: &Grammar, rule: &str, input: &str) -> Result<usize, Failure> {
+    Interp::new(grammar).eval_rule(rule, input, 0).map(|m| m.consumed)
+}
+
+// Same as `parse`, but on failure renders a farthest-failure diagnostic
+// instead of the bare `Failure` marker.
+pub fn parse_with_diagnostics(grammar: &Grammar, rule: &str, input: &str) -> Result<usize, String> {
+    let interp = Interp::new(grammar);
+    match interp.eval_rule(rule, input, 0) {
+        Ok(m) => Ok(m.consumed),
+        Err(_) => Err(interp.errors().render(input)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grammar_from(src: &str) -> Grammar {
+        let tree: ParseTree = syn::parse_str(src).unwrap();
+        Grammar::from_definitions(tree)
+    }
+
+    #[test]
+    fn direct_left_recursion_grows_left_associative() {
+        let grammar = grammar_from(
+            r#"
+            #[left_recursive]
+            expr = expr "+" term | term
+            term = "1" | "2" | "3"
+            "#,
+        );
+        assert_eq!(parse(&grammar, "expr", "1+2+3").unwrap(), 5);
+    }
+
+    #[test]
+    fn non_recursive_rule_marked_left_recursive_still_matches() {
+        let grammar = grammar_from(
+            r#"
+            #[left_recursive]
+            digit = "1" | "2"
+            "#,
+        );
+        assert_eq!(parse(&grammar, "digit", "2").unwrap(), 1);
+    }
+
+    #[test]
+    fn memoized_rule_result_is_cached_per_offset() {
+        let grammar = grammar_from(
+            r#"
+            start = (b "y") | (b "z")
+            #[memoize]
+            b = "foo"
+            "#,
+        );
+        assert_eq!(parse(&grammar, "start", "fooz").unwrap(), 4);
+
+        let interp = Interp::new(&grammar);
+        assert_eq!(interp.eval_rule("start", "fooz", 0).unwrap().consumed, 4);
+        // the memo table holds an entry for `b` at offset 0, which the second
+        // `Choice` alternative's call to `b` reads back instead of re-running it
+        assert_eq!(
+            interp
+                .memo
+                .borrow()
+                .keys()
+                .filter(|(name, offset)| name == "b" && *offset == 0)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn indirect_left_recursion_through_a_memoized_rule_regrows() {
+        let grammar = grammar_from(
+            r#"
+            #[left_recursive]
+            expr = addend | term
+            #[memoize]
+            addend = expr "+" term
+            term = "1" | "2" | "3"
+            "#,
+        );
+        // `addend`'s first attempt at offset 0 fails (before `expr` has a
+        // seed to recurse into) and gets memoized as `Failed`; that stale
+        // entry has to be evicted on every later grow iteration or `expr`
+        // stops growing after its first term instead of consuming "1+2+3"
+        assert_eq!(parse(&grammar, "expr", "1+2+3").unwrap(), 5);
+    }
+
+    #[test]
+    fn char_class_matches_ranges_and_negation() {
+        let grammar = grammar_from(r#"word = ['a'-'z']+"#);
+        assert_eq!(parse(&grammar, "word", "hello world").unwrap(), 5);
+
+        let grammar = grammar_from(r#"word = [^'a'-'z']+"#);
+        assert_eq!(parse(&grammar, "word", "123 hello").unwrap(), 4);
+    }
+
+    #[test]
+    fn diagnostics_report_farthest_failure_with_expected_set() {
+        let grammar = grammar_from(r#"greeting = "hi" " " name
+name = "alice" | "bob""#);
+        let err = parse_with_diagnostics(&grammar, "greeting", "hi dave").unwrap_err();
+        assert_eq!(err, r#"expected "alice" or "bob" at line 1, column 4, found 'd'"#);
+    }
+
+    #[test]
+    fn cut_hard_fails_past_the_next_choice_alternative() {
+        let grammar = grammar_from(
+            r#"
+            stmt = "if" ^ "x" | "if" "y"
+            "#,
+        );
+        let interp = Interp::new(&grammar);
+        // without the cut, "ify" would simply fall through to the second
+        // alternative and match; the cut after "if" in the first alternative
+        // must stop that backtracking and hard-fail instead
+        assert_eq!(interp.eval_rule("stmt", "ify", 0), Err(Failure::Hard));
+    }
+
+    #[test]
+    fn cut_aborts_whole_repetition_instead_of_stopping_it() {
+        let grammar = grammar_from(r#"xs = ("a" ^ "b")*"#);
+        let interp = Interp::new(&grammar);
+        // first two repetitions match "ab" each, third commits on "a" then
+        // fails to find "b": this must hard-fail the whole rule, not just
+        // stop the repetition at the two matches already made
+        assert_eq!(interp.eval_rule("xs", "ababaX", 0), Err(Failure::Hard));
+    }
+
+    #[test]
+    fn sepby_respects_min_and_trailing_mode() {
+        let grammar = grammar_from(r#"list = "x" ** ",""#);
+        assert_eq!(parse(&grammar, "list", "x,x,x").unwrap(), 5);
+        assert_eq!(parse(&grammar, "list", "").unwrap(), 0);
+
+        let grammar = grammar_from(r#"list = "x" ++ ",""#);
+        assert!(parse(&grammar, "list", "").is_err());
+
+        let grammar = grammar_from(r#"list = "x" ** "," ?"#);
+        assert_eq!(parse(&grammar, "list", "x,x,").unwrap(), 4);
+
+        let grammar = grammar_from(r#"list = "x" ** ",""#);
+        assert_eq!(parse(&grammar, "list", "x,x,").unwrap(), 3);
+    }
+
+    #[test]
+    fn cut_inside_sepby_item_hard_fails_instead_of_stopping_the_list() {
+        let grammar = grammar_from(r#"list = ("x" ^ "y") ** ",""#);
+        let interp = Interp::new(&grammar);
+        // the item commits on "x" and then fails to find "y": that must
+        // hard-fail the whole list, not be swallowed as "zero items matched"
+        assert_eq!(interp.eval_rule("list", "xZ", 0), Err(Failure::Hard));
+    }
+}
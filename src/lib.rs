@@ -0,0 +1,6 @@
+mod error;
+mod interp;
+mod parser;
+
+pub use interp::{parse, parse_with_diagnostics, Grammar, Interp};
+pub use parser::ParseTree;
@@ -1,15 +1,52 @@
 use syn::{
-    parenthesized, parse::Parse, parse::ParseStream, token::Paren, token, Block, Ident, Type,
-    LitStr, Token,
+    braced, bracketed, parenthesized, parse::Parse, parse::ParseStream, token::Brace,
+    token::Bracket, token::Paren, token, Attribute, Block, Ident, LitChar, LitInt, Type, LitStr,
+    Token,
 };
 
 
+// flags attached to a single rule via leading attributes, e.g. `#[left_recursive]`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RuleOptions {
+    // allow this rule to call itself (directly or indirectly) as its own left-most
+    // sub-parser; the interpreter grows the match with Warth's seed-growing algorithm
+    // instead of recursing forever
+    pub left_recursive: bool,
+    // memoize this rule's result per input offset in a packrat table instead of
+    // re-running its body every time it's reached, trading memory for guaranteed
+    // linear-time parsing
+    pub memoize: bool,
+}
+
+fn parse_rule_options(input: ParseStream) -> syn::Result<RuleOptions> {
+    let mut options = RuleOptions::default();
+
+    for attr in input.call(Attribute::parse_outer)? {
+        if attr.path().is_ident("left_recursive") {
+            options.left_recursive = true;
+        } else if attr.path().is_ident("memoize") {
+            options.memoize = true;
+        } else {
+            return Err(syn::Error::new_spanned(attr, "unknown rule attribute"));
+        }
+    }
+
+    // left recursion is only sound on top of a memo table, since the seed-growing
+    // loop re-enters the same (rule, offset) pair and relies on reading back its
+    // own in-progress result
+    if options.left_recursive {
+        options.memoize = true;
+    }
+
+    Ok(options)
+}
+
 #[derive(Debug)]
 pub enum ParseTree {
     // list of all the non-terminals in this grammar
     DefinitionList(Vec<ParseTree>),
     // definition of a new non-terminal
-    ParserDefinition(Ident, Option<Type>, Box<ParseTree>),
+    ParserDefinition(Ident, Option<Type>, Box<ParseTree>, RuleOptions),
 
     // the sub-parser is wrapped in `<...>` or `<ident: ...>`
     Capture(Box<ParseTree>, Option<Ident>),
@@ -24,6 +61,9 @@ pub enum ParseTree {
     Empty,
     // string literal
     Terminal(String),
+    // bracketed character class, e.g. `['a'-'z' '0'-'9' '_']` or `[^'\n']`; matches
+    // exactly one char falling inside (or, if negated, outside) any of `ranges`
+    CharClass { ranges: Vec<(char, char)>, negated: bool },
     // ordered list of alternative sub-parsers
     Choice(Vec<ParseTree>),
     // Repetition: 0 or more times
@@ -36,6 +76,44 @@ pub enum ParseTree {
     Peek(Box<ParseTree>),
     // negates the result of the sub-parser
     Not(Box<ParseTree>),
+    // once the sub-parser succeeds, commits the enclosing `Sequence`: any later
+    // failure in that sequence becomes a hard failure that propagates past
+    // enclosing `Choice` nodes instead of backtracking into the next alternative
+    Cut(Box<ParseTree>),
+    // matches the sub-parser between `min` and `max` times (inclusive); `max of
+    // None` means unbounded, e.g. `digit{2,4}` or `digit{3,}`
+    Repeat { item: Box<ParseTree>, min: usize, max: Option<usize> },
+    // matches `item (sep item)*` (or `item (sep item)+` when `min` is 1), e.g.
+    // `ident ** ","`; a match reports only the bytes consumed, the matched
+    // `item`s are not collected
+    SepBy { item: Box<ParseTree>, sep: Box<ParseTree>, min: usize, allow_trailing: bool },
+}
+
+impl ParseTree {
+    // Human-readable description of what this node matches, used by `Interp` to
+    // populate `error::ParseErrorState` when a leaf matcher fails. Wrapper nodes
+    // (captures, repetitions, lookaheads, cuts) report the label of what they
+    // wrap; `None` for sequences, choices, `Empty` and grammar-level nodes.
+    pub fn expected_label(&self) -> Option<String> {
+        match self {
+            ParseTree::Terminal(s) => Some(format!("{:?}", s)),
+            ParseTree::CharClass { negated: false, .. } => Some("character class".to_string()),
+            ParseTree::CharClass { negated: true, .. } => Some("negated character class".to_string()),
+            ParseTree::Call(ident) => Some(format!("{}", ident)),
+            ParseTree::NonTerminal(ident) => Some(format!("{}", ident)),
+            // these don't fail on their own; forward to whatever they wrap
+            ParseTree::Capture(inner, _)
+            | ParseTree::Optional(inner)
+            | ParseTree::Many0(inner)
+            | ParseTree::Many1(inner)
+            | ParseTree::Peek(inner)
+            | ParseTree::Not(inner)
+            | ParseTree::Cut(inner) => inner.expected_label(),
+            ParseTree::Repeat { item, .. } => item.expected_label(),
+            ParseTree::SepBy { item, .. } => item.expected_label(),
+            _ => None,
+        }
+    }
 }
 
 
@@ -48,6 +126,39 @@ enum Postfix {
     Optional,
     Many0,
     Many1,
+    Cut,
+    Repeat { min: usize, max: Option<usize> },
+    SepBy { sep: Box<ParseTree>, min: usize, allow_trailing: bool },
+}
+
+// Parses the body of a `{...}` bound: `n`, `n,` or `n,m`.
+fn parse_repeat_bound(input: ParseStream) -> syn::Result<(usize, Option<usize>)> {
+    let content;
+    braced!(content in input);
+
+    let min_lit = content.parse::<LitInt>()?;
+    let min = min_lit.base10_parse::<usize>()?;
+
+    if !content.peek(Token![,]) {
+        return Ok((min, Some(min)));
+    }
+    content.parse::<Token![,]>()?; // just skip past this
+
+    if content.is_empty() {
+        return Ok((min, None));
+    }
+
+    let max_lit = content.parse::<LitInt>()?;
+    let max = max_lit.base10_parse::<usize>()?;
+
+    if max < min {
+        return Err(syn::Error::new(
+            max_lit.span(),
+            format!("repetition upper bound {} is less than lower bound {}", max, min),
+        ));
+    }
+
+    Ok((min, Some(max)))
 }
 
 fn parse_prefix(input: ParseStream) -> Option<Prefix> {
@@ -66,12 +177,39 @@ fn parse_prefix(input: ParseStream) -> Option<Prefix> {
     }
 }
 
-fn parse_postfix(input: ParseStream) -> Option<Postfix> {
+// A `?` right after a `**`/`++` separator opts into allowing a trailing
+// separator with no following item, e.g. `ident ** "," ?` accepts `"a,b,"`.
+fn parse_trailing_marker(input: ParseStream) -> bool {
+    if input.peek(Token![?]) {
+        input.parse::<Token![?]>().unwrap(); // just skip past this
+        true
+    } else {
+        false
+    }
+}
+
+fn parse_postfix(input: ParseStream) -> syn::Result<Option<Postfix>> {
     let lookahead = input.lookahead1();
-    if lookahead.peek(Token![?]) {
+    Ok(if lookahead.peek(Token![?]) {
         // Optional
         input.parse::<Token![?]>().unwrap(); // just skip past this
         Some(Postfix::Optional)
+    } else if input.peek(Token![*]) && input.peek2(Token![*]) {
+        // SepBy, zero or more: `item ** sep`, optionally `item ** sep ?` to
+        // allow a trailing separator with no item after it
+        input.parse::<Token![*]>().unwrap(); // just skip past this
+        input.parse::<Token![*]>().unwrap(); // just skip past this
+        let sep = Box::new(parse_atom(input)?);
+        let allow_trailing = parse_trailing_marker(input);
+        Some(Postfix::SepBy { sep, min: 0, allow_trailing })
+    } else if input.peek(Token![+]) && input.peek2(Token![+]) {
+        // SepBy, one or more: `item ++ sep`, optionally `item ++ sep ?` to
+        // allow a trailing separator with no item after it
+        input.parse::<Token![+]>().unwrap(); // just skip past this
+        input.parse::<Token![+]>().unwrap(); // just skip past this
+        let sep = Box::new(parse_atom(input)?);
+        let allow_trailing = parse_trailing_marker(input);
+        Some(Postfix::SepBy { sep, min: 1, allow_trailing })
     } else if lookahead.peek(Token![*]) {
         // Many0
         input.parse::<Token![*]>().unwrap(); // just skip past this
@@ -80,18 +218,66 @@ fn parse_postfix(input: ParseStream) -> Option<Postfix> {
         // Many1
         input.parse::<Token![+]>().unwrap(); // just skip past this
         Some(Postfix::Many1)
+    } else if lookahead.peek(Token![^]) {
+        // Cut
+        input.parse::<Token![^]>().unwrap(); // just skip past this
+        Some(Postfix::Cut)
+    } else if lookahead.peek(Brace) {
+        // Bounded repetition: `{n}`, `{n,}`, `{n,m}`
+        let (min, max) = parse_repeat_bound(input)?;
+        Some(Postfix::Repeat { min, max })
     } else {
         // No postfix found
         None
-    }
+    })
 }
 
-fn parse_element(input: ParseStream) -> syn::Result<ParseTree> {
-    let prefix = parse_prefix(input);
+fn parse_char_class(input: ParseStream) -> syn::Result<ParseTree> {
+    let content;
+    bracketed!(content in input);
+
+    let negated = if content.peek(Token![^]) {
+        content.parse::<Token![^]>()?; // just skip past this
+        true
+    } else {
+        false
+    };
+
+    let mut ranges = Vec::with_capacity(4);
+    while !content.is_empty() {
+        let start_lit = content.parse::<LitChar>()?;
+        let start = start_lit.value();
+
+        let (end, end_span) = if content.peek(Token![-]) {
+            content.parse::<Token![-]>()?; // just skip past this
+            let end_lit = content.parse::<LitChar>()?;
+            (end_lit.value(), end_lit.span())
+        } else {
+            (start, start_lit.span())
+        };
+
+        if end < start {
+            return Err(syn::Error::new(
+                end_span,
+                format!("invalid character range: '{}' is after '{}'", start, end),
+            ));
+        }
+
+        ranges.push((start, end));
+    }
+
+    Ok(ParseTree::CharClass { ranges, negated })
+}
 
+// Parses a single atom with neither prefix nor postfix applied: a
+// non-terminal, external call, terminal, character class, grouping, or
+// capture. Used directly (without postfix) to parse `**`/`++` separators, so
+// that a trailing `?` there can only mean "allow a trailing separator" and
+// never gets mistaken for an `Optional` postfix on the separator itself.
+fn parse_atom(input: ParseStream) -> syn::Result<ParseTree> {
     let lookahead = input.lookahead1();
 
-    let mut parsed = if lookahead.peek(Ident) {
+    if lookahead.peek(Ident) {
         // if there's an '=' sign following it's the start of a new definition
         if parse_definition(&input.fork()).is_ok() {
         // if (input.peek2(Token![=]) && !input.peek2(Token![=>])) || input.peek2(Token![:]) {
@@ -107,6 +293,9 @@ fn parse_element(input: ParseStream) -> syn::Result<ParseTree> {
     } else if lookahead.peek(LitStr) {
         // Terminal
         Ok(ParseTree::Terminal(input.parse::<LitStr>()?.value()))
+    } else if lookahead.peek(Bracket) {
+        // Character class
+        parse_char_class(input)
     } else if lookahead.peek(Paren) {
         // Grouping
         // Get content of parens
@@ -136,27 +325,38 @@ fn parse_element(input: ParseStream) -> syn::Result<ParseTree> {
         Ok(ParseTree::Capture(Box::new(term), ident))
     } else {
         Err(lookahead.error())
+    }
+}
+
+fn parse_element(input: ParseStream) -> syn::Result<ParseTree> {
+    let prefix = parse_prefix(input);
+    // bail out before ever touching `parse_postfix`: its `**`/`++`/`{...}`
+    // branches consume real tokens off this same `ParseStream` with no
+    // fork/rollback, so running them after a failed atom would silently eat
+    // whatever stray postfix-shaped token comes next instead of surfacing
+    // this error
+    let atom = parse_atom(input)?;
+
+    let postfix = parse_postfix(input)?;
+
+    let with_postfix = match postfix {
+        Some(Postfix::Optional) => ParseTree::Optional(Box::new(atom)),
+        Some(Postfix::Many0) => ParseTree::Many0(Box::new(atom)),
+        Some(Postfix::Many1) => ParseTree::Many1(Box::new(atom)),
+        Some(Postfix::Cut) => ParseTree::Cut(Box::new(atom)),
+        Some(Postfix::Repeat { min, max }) => {
+            ParseTree::Repeat { item: Box::new(atom), min, max }
+        }
+        Some(Postfix::SepBy { sep, min, allow_trailing }) => {
+            ParseTree::SepBy { item: Box::new(atom), sep, min, allow_trailing }
+        }
+        None => atom,
     };
 
-    let postfix = parse_postfix(input);
-
-    // process postfix
-    parsed = parsed.and_then(|p| {
-        Ok(match postfix {
-            Some(Postfix::Optional) => ParseTree::Optional(Box::new(p)),
-            Some(Postfix::Many0) => ParseTree::Many0(Box::new(p)),
-            Some(Postfix::Many1) => ParseTree::Many1(Box::new(p)),
-            None => p,
-        })
-    });
-
-    // process prefix
-    parsed.and_then(|p| {
-        Ok(match prefix {
-            Some(Prefix::Peek) => ParseTree::Peek(Box::new(p)),
-            Some(Prefix::Not) => ParseTree::Not(Box::new(p)),
-            None => p,
-        })
+    Ok(match prefix {
+        Some(Prefix::Peek) => ParseTree::Peek(Box::new(with_postfix)),
+        Some(Prefix::Not) => ParseTree::Not(Box::new(with_postfix)),
+        None => with_postfix,
     })
 }
 
@@ -209,6 +409,9 @@ fn parse_expression(input: ParseStream) -> syn::Result<ParseTree> {
 }
 
 fn parse_definition(input: ParseStream) -> syn::Result<ParseTree> {
+    // parse any leading `#[...]` rule attributes, e.g. `#[left_recursive]`
+    let options = parse_rule_options(input)?;
+
     // parse name
     let name = input.parse::<Ident>()?;
 
@@ -227,7 +430,7 @@ fn parse_definition(input: ParseStream) -> syn::Result<ParseTree> {
     let expression = parse_expression(input)?;
 
     // Final ast node
-    Ok(ParseTree::ParserDefinition(name, return_type, Box::new(expression)))
+    Ok(ParseTree::ParserDefinition(name, return_type, Box::new(expression), options))
 }
 
 impl Parse for ParseTree {
@@ -241,3 +444,138 @@ impl Parse for ParseTree {
         Ok(ParseTree::DefinitionList(definitions))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_rule(src: &str) -> ParseTree {
+        let ParseTree::DefinitionList(mut defs) = syn::parse_str::<ParseTree>(src).unwrap() else {
+            panic!("expected a definition list");
+        };
+        defs.remove(0)
+    }
+
+    // a rule body is always a `Sequence`, even with a single element; unwrap
+    // that to get at the element itself
+    fn only_element(body: ParseTree) -> ParseTree {
+        let ParseTree::Sequence(mut items, _) = body else { panic!("expected Sequence") };
+        assert_eq!(items.len(), 1);
+        items.remove(0)
+    }
+
+    #[test]
+    fn left_recursive_attribute_sets_flag_and_implies_memoize() {
+        let ParseTree::ParserDefinition(_, _, _, options) =
+            parse_rule(r#"#[left_recursive] expr = expr "+" term | term"#)
+        else {
+            panic!("expected a parser definition");
+        };
+        assert!(options.left_recursive);
+        assert!(options.memoize);
+    }
+
+    #[test]
+    fn unknown_rule_attribute_is_rejected() {
+        assert!(syn::parse_str::<ParseTree>(r#"#[bogus] a = "x""#).is_err());
+    }
+
+    #[test]
+    fn char_class_parses_ranges_and_negation() {
+        let ParseTree::ParserDefinition(_, _, body, _) =
+            parse_rule(r#"word = ['a'-'z' '_']+"#)
+        else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::Many1(inner) = only_element(*body) else { panic!("expected Many1") };
+        let ParseTree::CharClass { ranges, negated } = *inner else {
+            panic!("expected CharClass")
+        };
+        assert!(!negated);
+        assert_eq!(ranges, vec![('a', 'z'), ('_', '_')]);
+    }
+
+    #[test]
+    fn char_class_rejects_backwards_range() {
+        assert!(syn::parse_str::<ParseTree>(r#"word = ['z'-'a']"#).is_err());
+    }
+
+    #[test]
+    fn cut_postfix_parses_into_cut_node() {
+        let ParseTree::ParserDefinition(_, _, body, _) =
+            parse_rule(r#"stmt = "if" ^ "x""#)
+        else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::Sequence(items, _) = *body else { panic!("expected Sequence") };
+        assert!(matches!(items[0], ParseTree::Cut(_)));
+    }
+
+    #[test]
+    fn repeat_bound_parses_all_three_forms() {
+        let ParseTree::ParserDefinition(_, _, body, _) = parse_rule(r#"d = digit{2,4}"#) else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::Repeat { min, max, .. } = only_element(*body) else {
+            panic!("expected Repeat")
+        };
+        assert_eq!((min, max), (2, Some(4)));
+
+        let ParseTree::ParserDefinition(_, _, body, _) = parse_rule(r#"d = digit{3}"#) else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::Repeat { min, max, .. } = only_element(*body) else {
+            panic!("expected Repeat")
+        };
+        assert_eq!((min, max), (3, Some(3)));
+
+        let ParseTree::ParserDefinition(_, _, body, _) = parse_rule(r#"d = digit{3,}"#) else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::Repeat { min, max, .. } = only_element(*body) else {
+            panic!("expected Repeat")
+        };
+        assert_eq!((min, max), (3, None));
+    }
+
+    #[test]
+    fn repeat_bound_rejects_max_less_than_min() {
+        assert!(syn::parse_str::<ParseTree>(r#"d = digit{5,2}"#).is_err());
+    }
+
+    #[test]
+    fn sepby_trailing_marker_sets_allow_trailing() {
+        let ParseTree::ParserDefinition(_, _, body, _) =
+            parse_rule(r#"list = ident ** "," ?"#)
+        else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::SepBy { allow_trailing, min, .. } = only_element(*body) else {
+            panic!("expected SepBy")
+        };
+        assert_eq!(min, 0);
+        assert!(allow_trailing);
+    }
+
+    #[test]
+    fn sepby_without_marker_disallows_trailing() {
+        let ParseTree::ParserDefinition(_, _, body, _) =
+            parse_rule(r#"list = ident ++ ",""#)
+        else {
+            panic!("expected a parser definition");
+        };
+        let ParseTree::SepBy { allow_trailing, min, .. } = only_element(*body) else {
+            panic!("expected SepBy")
+        };
+        assert_eq!(min, 1);
+        assert!(!allow_trailing);
+    }
+
+    #[test]
+    fn stray_postfix_after_a_finished_sequence_is_rejected() {
+        // the second `{3}` has nothing to attach to: `"x"{2}` already forms a
+        // complete element, so this must be a syntax error rather than
+        // silently discarding the trailing `{3}`
+        assert!(syn::parse_str::<ParseTree>("a = \"x\" {2} {3}\nb = \"y\"").is_err());
+    }
+}